@@ -14,13 +14,17 @@ use nalgebra::Isometry2;
 use specs::{Entity, LazyUpdate};
 use std::any::Any;
 use std::collections::HashSet;
-use std::ops::Deref;
-use std::sync::{Arc, Mutex};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
 use strum_macros::EnumCount;
 
 pub enum StatusStackingResult {
     DontAddTheNewStatus,
     AddTheNewStatus,
+    // the existing instance already merged the incoming status onto itself (e.g. extended
+    // its duration), so the incoming one must be dropped without occupying a new slot
+    RefreshExisting,
 }
 
 pub trait Status: Any {
@@ -45,11 +49,20 @@ pub trait Status: Any {
         system_vars: &mut SystemVariables,
         entities: &specs::Entities,
         updater: &mut specs::Write<LazyUpdate>,
+        params: &mut StatusUpdateParams,
     ) -> StatusUpdateResult;
 
     fn affect_incoming_damage(&mut self, outcome: AttackOutcome) -> AttackOutcome;
+    fn affect_outgoing_damage(&mut self, outcome: AttackOutcome) -> AttackOutcome;
     fn allow_push(&mut self, push: &ApplyForceComponent) -> bool;
 
+    fn on_apply(&mut self, _self_char_id: Entity, _system_vars: &mut SystemVariables) {}
+    fn on_remove(&mut self, _self_char_id: Entity, _system_vars: &mut SystemVariables) {}
+
+    fn add_suppression(&mut self);
+    fn remove_suppression(&mut self);
+    fn is_suppressed(&self) -> bool;
+
     fn render(
         &self,
         char_pos: &WorldCoords,
@@ -60,6 +73,9 @@ pub trait Status: Any {
     fn get_status_completion_percent(&self, now: ElapsedTime) -> Option<(ElapsedTime, f32)>;
 
     fn stack(&mut self, other: Box<dyn Status>) -> StatusStackingResult;
+
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
 // TODO: should 'Dead' be a status?
@@ -71,7 +87,9 @@ pub enum MainStatuses {
 }
 
 #[derive(Clone)]
-struct MountedStatus;
+struct MountedStatus {
+    suppressed: usize,
+}
 
 const STATUS_ARRAY_SIZE: usize = 32;
 pub struct Statuses {
@@ -101,7 +119,11 @@ impl Statuses {
             .take(self.first_free_index)
             .filter(|it| it.is_some())
         {
-            allow &= status.as_ref().unwrap().lock().unwrap().allow_push(push);
+            let mut guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                continue;
+            }
+            allow &= guard.allow_push(push);
         }
         return allow;
     }
@@ -113,12 +135,27 @@ impl Statuses {
             .take(self.first_free_index)
             .filter(|it| it.is_some())
         {
-            outcome = status
-                .as_ref()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .affect_incoming_damage(outcome);
+            let mut guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                continue;
+            }
+            outcome = guard.affect_incoming_damage(outcome);
+        }
+        return outcome;
+    }
+
+    pub fn affect_outgoing_damage(&mut self, mut outcome: AttackOutcome) -> AttackOutcome {
+        for status in self
+            .statuses
+            .iter_mut()
+            .take(self.first_free_index)
+            .filter(|it| it.is_some())
+        {
+            let mut guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                continue;
+            }
+            outcome = guard.affect_outgoing_damage(outcome);
         }
         return outcome;
     }
@@ -132,25 +169,81 @@ impl Statuses {
         updater: &mut specs::Write<LazyUpdate>,
     ) -> bool {
         let mut changed = false;
+        let mut apply_cmds = Vec::new();
+        let mut remove_cmds = Vec::new();
         for status in self
             .statuses
             .iter_mut()
             .take(self.first_free_index)
             .filter(|it| it.is_some())
         {
-            let result = status.as_ref().unwrap().lock().unwrap().update(
+            let mut guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                // suppressed statuses are skipped entirely and stay in the array. Their end
+                // times are absolute (e.g. PoisonStatus::until), so suppression does not
+                // extend them: a status whose end time passed while it was suppressed is
+                // removed on its first update after being unsuppressed
+                continue;
+            }
+            let mut params = StatusUpdateParams::new();
+            let result = guard.update(
                 self_char_id,
                 char_pos,
                 system_vars,
                 entities,
                 updater,
+                &mut params,
             );
-            match result {
-                StatusUpdateResult::RemoveIt => {
-                    *status = None;
-                    changed = true;
+            let should_remove = match result {
+                StatusUpdateResult::RemoveIt => true,
+                StatusUpdateResult::KeepIt => params.mark_for_deletion,
+            };
+            if should_remove {
+                guard.on_remove(self_char_id, system_vars);
+            }
+            drop(guard);
+            apply_cmds.append(&mut params.apply);
+            remove_cmds.append(&mut params.remove);
+            if should_remove {
+                *status = None;
+                changed = true;
+            }
+        }
+        // commands are drained only after the iteration above completes, so no status
+        // ever mutates the array it is currently being iterated over. `Statuses` only owns
+        // self_char_id's own array, so it can only act on commands targeting self_char_id;
+        // a status targeting another entity (e.g. a poison that wants to spread on tick)
+        // needs a system that reads ApplyStatusComponent/RemoveStatusComponent by
+        // target_entity_id and forwards it to that entity's own Statuses - that dispatch
+        // doesn't exist yet, so such commands are logged and dropped rather than silently
+        // mis-applied to self_char_id.
+        for comp in apply_cmds {
+            if comp.target_entity_id != self_char_id {
+                log::warn!("Dropping ApplyStatusComponent targeting a different entity than the one being updated; cross-entity status dispatch isn't wired up yet");
+                continue;
+            }
+            match comp.status {
+                ApplyStatusComponentPayload::SecondaryStatus(arc_status) => {
+                    self.add(arc_status, self_char_id, system_vars)
+                }
+                ApplyStatusComponentPayload::MainStatus(_) => {
+                    // TODO: applying a MainStatus from here needs extra data (e.g. Poison's
+                    // start/end times) that ApplyStatusComponent doesn't carry yet
+                }
+            }
+        }
+        for comp in remove_cmds {
+            if comp.target_entity_id != self_char_id {
+                log::warn!("Dropping RemoveStatusComponent targeting a different entity than the one being updated; cross-entity status dispatch isn't wired up yet");
+                continue;
+            }
+            match comp.status {
+                RemoveStatusComponentPayload::MainStatus(m) => {
+                    self.remove_main_status(m, self_char_id, system_vars)
+                }
+                RemoveStatusComponentPayload::SecondaryStatus(status_type) => {
+                    self.remove(status_type, self_char_id, system_vars)
                 }
-                StatusUpdateResult::KeepIt => {}
             }
         }
         while self.first_free_index > MAINSTATUSES_COUNT
@@ -217,12 +310,11 @@ impl Statuses {
             .take(self.first_free_index)
             .filter(|it| it.is_some())
         {
-            status
-                .as_ref()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .calc_attribs(&mut self.cached_modifier_collector);
+            let guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                continue;
+            }
+            guard.calc_attribs(&mut self.cached_modifier_collector);
         }
         return &self.cached_modifier_collector;
     }
@@ -244,13 +336,11 @@ impl Statuses {
             .take(self.first_free_index)
             .filter(|it| it.is_some())
         {
-            if let Some(spr) = status
-                .as_ref()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .calc_render_sprite(job_id, head_index, sex, sprites)
-            {
+            let guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                continue;
+            }
+            if let Some(spr) = guard.calc_render_sprite(job_id, head_index, sex, sprites) {
                 sprite = spr;
             }
         }
@@ -265,7 +355,11 @@ impl Statuses {
             .take(self.first_free_index)
             .filter(|it| it.is_some())
         {
-            let status_color = status.as_ref().unwrap().lock().unwrap().get_render_color();
+            let guard = status.as_ref().unwrap().lock().unwrap();
+            if guard.is_suppressed() {
+                continue;
+            }
+            let status_color = guard.get_render_color();
             for i in 0..4 {
                 ret[i] *= status_color[i];
             }
@@ -312,78 +406,238 @@ impl Statuses {
         self.statuses[MainStatuses::Stun as usize].is_some()
     }
 
-    pub fn switch_mounted(&mut self) {
+    pub fn switch_mounted(&mut self, self_char_id: Entity, system_vars: &mut SystemVariables) {
         let is_mounted = self.statuses[MainStatuses::Mounted as usize].is_some();
-        let value: Option<Arc<Mutex<Box<dyn Status>>>> = if !is_mounted {
-            Some(Arc::new(Mutex::new(Box::new(MountedStatus {}))))
+        if is_mounted {
+            if let Some(existing) = &self.statuses[MainStatuses::Mounted as usize] {
+                existing
+                    .lock()
+                    .unwrap()
+                    .on_remove(self_char_id, system_vars);
+            }
+            self.statuses[MainStatuses::Mounted as usize] = None;
         } else {
-            None
+            let status: Arc<Mutex<Box<dyn Status>>> =
+                Arc::new(Mutex::new(Box::new(MountedStatus { suppressed: 0 })));
+            status.lock().unwrap().on_apply(self_char_id, system_vars);
+            self.statuses[MainStatuses::Mounted as usize] = Some(status);
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        status: Arc<Mutex<Box<dyn Status>>>,
+        self_char_id: Entity,
+        system_vars: &mut SystemVariables,
+    ) {
+        let new_type_id = status.lock().unwrap().as_any().type_id();
+        let existing_index = self
+            .statuses
+            .iter()
+            .take(self.first_free_index)
+            .position(|it| {
+                it.as_ref()
+                    .map(|existing| existing.lock().unwrap().as_any().type_id() == new_type_id)
+                    .unwrap_or(false)
+            });
+
+        let existing_index = match existing_index {
+            Some(index) => index,
+            None => {
+                self.append(status, self_char_id, system_vars);
+                return;
+            }
+        };
+
+        // `status` is normally the sole owner of its Arc at this point (it was just built for
+        // this call), so this almost always succeeds; fall back to `dupl` otherwise.
+        let new_status: Box<dyn Status> = match Arc::try_unwrap(status) {
+            Ok(mutex) => mutex.into_inner().unwrap(),
+            Err(arc) => arc.lock().unwrap().dupl(),
         };
-        self.statuses[MainStatuses::Mounted as usize] = value;
+        let spare = new_status.dupl();
+        let result = self.statuses[existing_index]
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .stack(new_status);
+        match result {
+            StatusStackingResult::DontAddTheNewStatus => {}
+            StatusStackingResult::AddTheNewStatus => {
+                self.append(Arc::new(Mutex::new(spare)), self_char_id, system_vars);
+            }
+            StatusStackingResult::RefreshExisting => {}
+        }
     }
 
-    pub fn add(&mut self, status: Arc<Mutex<Box<dyn Status>>>) {
+    fn append(
+        &mut self,
+        status: Arc<Mutex<Box<dyn Status>>>,
+        self_char_id: Entity,
+        system_vars: &mut SystemVariables,
+    ) {
         if self.first_free_index >= STATUS_ARRAY_SIZE {
             log::error!("There is no more space for new Status!");
             return;
         }
+        status.lock().unwrap().on_apply(self_char_id, system_vars);
         self.statuses[self.first_free_index] = Some(status);
         self.first_free_index += 1;
     }
 
-    pub fn remove_all(&mut self) {
+    pub fn remove_all(&mut self, self_char_id: Entity, system_vars: &mut SystemVariables) {
         for status in self.statuses.iter_mut().take(self.first_free_index) {
+            if let Some(status) = status {
+                status.lock().unwrap().on_remove(self_char_id, system_vars);
+            }
             *status = None;
         }
         self.first_free_index = MAINSTATUSES_COUNT;
     }
 
-    pub fn remove(&mut self, status_type: StatusType) {
+    pub fn remove(
+        &mut self,
+        status_type: StatusType,
+        self_char_id: Entity,
+        system_vars: &mut SystemVariables,
+    ) {
         for arc_status in self.statuses.iter_mut().take(self.first_free_index) {
             let should_remove = arc_status
                 .as_ref()
                 .map(|it| it.lock().unwrap().typ() == status_type)
                 .unwrap_or(false);
             if should_remove {
+                if let Some(arc_status) = arc_status {
+                    arc_status
+                        .lock()
+                        .unwrap()
+                        .on_remove(self_char_id, system_vars);
+                }
                 *arc_status = None;
             }
         }
     }
 
-    pub fn remove_main_status(&mut self, status: MainStatuses) {
+    pub fn remove_main_status(
+        &mut self,
+        status: MainStatuses,
+        self_char_id: Entity,
+        system_vars: &mut SystemVariables,
+    ) {
+        if let Some(existing) = &self.statuses[status as usize] {
+            existing
+                .lock()
+                .unwrap()
+                .on_remove(self_char_id, system_vars);
+        }
         self.statuses[status as usize] = None;
     }
 
-    pub unsafe fn hack_cast<T>(boxx: &Box<dyn Status>) -> &T {
-        // TODO: I could not get back a PosionStatus struct from a Status trait without unsafe, HELP
-        // hacky as hell, nothing guarantees that the first pointer in a Trait is the value pointer
-        let raw_object: *const T = std::mem::transmute(boxx);
-        return &*raw_object;
+    // increments the suppression counter instead of removing, so caster-tracked state survives
+    pub fn suppress(&mut self, status_type: StatusType) {
+        for arc_status in self.statuses.iter().take(self.first_free_index) {
+            if let Some(arc_status) = arc_status {
+                let mut guard = arc_status.lock().unwrap();
+                if guard.typ() == status_type {
+                    guard.add_suppression();
+                }
+            }
+        }
+    }
+
+    pub fn unsuppress(&mut self, status_type: StatusType) {
+        for arc_status in self.statuses.iter().take(self.first_free_index) {
+            if let Some(arc_status) = arc_status {
+                let mut guard = arc_status.lock().unwrap();
+                if guard.typ() == status_type {
+                    guard.remove_suppression();
+                }
+            }
+        }
+    }
+
+    pub fn suppress_main_status(&mut self, status: MainStatuses) {
+        if let Some(arc_status) = &self.statuses[status as usize] {
+            arc_status.lock().unwrap().add_suppression();
+        }
+    }
+
+    pub fn unsuppress_main_status(&mut self, status: MainStatuses) {
+        if let Some(arc_status) = &self.statuses[status as usize] {
+            arc_status.lock().unwrap().remove_suppression();
+        }
+    }
+
+    // finds the first status of `status_type` that is concretely a `T`, locking its slot
+    pub fn get<T: Status>(&self, status_type: StatusType) -> Option<StatusRef<T>> {
+        for arc_status in self.statuses.iter().take(self.first_free_index) {
+            if let Some(arc_status) = arc_status {
+                let guard = arc_status.lock().unwrap();
+                if guard.typ() == status_type && guard.as_any().is::<T>() {
+                    return Some(StatusRef {
+                        guard,
+                        _marker: PhantomData,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get_mut<T: Status>(&mut self, status_type: StatusType) -> Option<StatusRefMut<T>> {
+        for arc_status in self.statuses.iter().take(self.first_free_index) {
+            if let Some(arc_status) = arc_status {
+                let guard = arc_status.lock().unwrap();
+                if guard.typ() == status_type && guard.as_any().is::<T>() {
+                    return Some(StatusRefMut {
+                        guard,
+                        _marker: PhantomData,
+                    });
+                }
+            }
+        }
+        None
     }
 
     pub fn add_poison(
         &mut self,
         poison_caster_entity_id: Entity,
+        self_char_id: Entity,
         started: ElapsedTime,
         until: ElapsedTime,
+        system_vars: &mut SystemVariables,
     ) {
-        let new_until = {
-            let status = &self.statuses[MainStatuses::Poison as usize];
-            if let Some(current_poison) = status {
-                let boxx: &Box<dyn Status> = &*current_poison.lock().unwrap();
-                unsafe { Statuses::hack_cast::<PoisonStatus>(boxx).until.max(until) }
-            } else {
-                until
+        let new_status: Box<dyn Status> = Box::new(PoisonStatus {
+            poison_caster_entity_id,
+            started,
+            until,
+            next_damage_at: started.add_seconds(1.0),
+            suppressed: 0,
+        });
+
+        match &self.statuses[MainStatuses::Poison as usize] {
+            // an existing poison merges the incoming one onto itself via `stack`, extending
+            // `until`/resetting `next_damage_at` rather than occupying a new slot
+            Some(existing) => {
+                let spare = new_status.dupl();
+                let result = existing.lock().unwrap().stack(new_status);
+                match result {
+                    StatusStackingResult::DontAddTheNewStatus => {}
+                    StatusStackingResult::AddTheNewStatus => {
+                        let status = Arc::new(Mutex::new(spare));
+                        status.lock().unwrap().on_apply(self_char_id, system_vars);
+                        self.statuses[MainStatuses::Poison as usize] = Some(status);
+                    }
+                    StatusStackingResult::RefreshExisting => {}
+                }
             }
-        };
-
-        self.statuses[MainStatuses::Poison as usize] =
-            Some(Arc::new(Mutex::new(Box::new(PoisonStatus {
-                poison_caster_entity_id,
-                started,
-                until: new_until,
-                next_damage_at: started.add_seconds(1.0),
-            }))));
+            None => {
+                let status = Arc::new(Mutex::new(new_status));
+                status.lock().unwrap().on_apply(self_char_id, system_vars);
+                self.statuses[MainStatuses::Poison as usize] = Some(status);
+            }
+        }
     }
 }
 
@@ -392,9 +646,60 @@ pub enum StatusUpdateResult {
     KeepIt,
 }
 
+// read-only handle to a concrete `Status`, holding its slot's lock for as long as it's alive
+pub struct StatusRef<'a, T: Status> {
+    guard: MutexGuard<'a, Box<dyn Status>>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: Status> Deref for StatusRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_any().downcast_ref::<T>().unwrap()
+    }
+}
+
+pub struct StatusRefMut<'a, T: Status> {
+    guard: MutexGuard<'a, Box<dyn Status>>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: Status> Deref for StatusRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_any().downcast_ref::<T>().unwrap()
+    }
+}
+
+impl<'a, T: Status> DerefMut for StatusRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_any_mut().downcast_mut::<T>().unwrap()
+    }
+}
+
+// deferred apply/remove/delete commands for `Status::update` to avoid re-entering `Statuses`
+// while it's being iterated; drained self-only, since there's no cross-entity dispatch yet
+pub struct StatusUpdateParams {
+    pub apply: Vec<ApplyStatusComponent>,
+    pub remove: Vec<RemoveStatusComponent>,
+    pub mark_for_deletion: bool,
+}
+
+impl StatusUpdateParams {
+    pub fn new() -> StatusUpdateParams {
+        StatusUpdateParams {
+            apply: Vec::new(),
+            remove: Vec::new(),
+            mark_for_deletion: false,
+        }
+    }
+}
+
 impl Status for MountedStatus {
     fn dupl(&self) -> Box<dyn Status> {
-        Box::new(MountedStatus)
+        Box::new(self.clone())
     }
 
     fn can_target_move(&self) -> bool {
@@ -443,6 +748,7 @@ impl Status for MountedStatus {
         _system_vars: &mut SystemVariables,
         _entities: &specs::Entities,
         _updater: &mut specs::Write<LazyUpdate>,
+        _params: &mut StatusUpdateParams,
     ) -> StatusUpdateResult {
         StatusUpdateResult::KeepIt
     }
@@ -451,6 +757,10 @@ impl Status for MountedStatus {
         outcome
     }
 
+    fn affect_outgoing_damage(&mut self, outcome: AttackOutcome) -> AttackOutcome {
+        outcome
+    }
+
     fn allow_push(&mut self, _push: &ApplyForceComponent) -> bool {
         true
     }
@@ -470,6 +780,26 @@ impl Status for MountedStatus {
     fn stack(&mut self, _other: Box<dyn Status>) -> StatusStackingResult {
         StatusStackingResult::DontAddTheNewStatus
     }
+
+    fn add_suppression(&mut self) {
+        self.suppressed += 1;
+    }
+
+    fn remove_suppression(&mut self) {
+        self.suppressed = self.suppressed.saturating_sub(1);
+    }
+
+    fn is_suppressed(&self) -> bool {
+        self.suppressed > 0
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 #[derive(Clone)]
@@ -478,6 +808,7 @@ pub struct PoisonStatus {
     pub started: ElapsedTime,
     pub until: ElapsedTime,
     pub next_damage_at: ElapsedTime,
+    pub suppressed: usize,
 }
 
 impl Status for PoisonStatus {
@@ -524,6 +855,7 @@ impl Status for PoisonStatus {
         system_vars: &mut SystemVariables,
         _entities: &specs::Entities,
         _updater: &mut specs::Write<LazyUpdate>,
+        _params: &mut StatusUpdateParams,
     ) -> StatusUpdateResult {
         if self.until.is_earlier_than(system_vars.time) {
             StatusUpdateResult::RemoveIt
@@ -544,6 +876,10 @@ impl Status for PoisonStatus {
         outcome
     }
 
+    fn affect_outgoing_damage(&mut self, outcome: AttackOutcome) -> AttackOutcome {
+        outcome
+    }
+
     fn allow_push(&mut self, _push: &ApplyForceComponent) -> bool {
         true
     }
@@ -567,8 +903,34 @@ impl Status for PoisonStatus {
         Some((self.until, now.percentage_between(self.started, self.until)))
     }
 
-    fn stack(&mut self, _other: Box<dyn Status>) -> StatusStackingResult {
-        StatusStackingResult::AddTheNewStatus
+    fn stack(&mut self, other: Box<dyn Status>) -> StatusStackingResult {
+        if let Some(other) = other.as_any().downcast_ref::<PoisonStatus>() {
+            self.until = self.until.max(other.until);
+            self.started = other.started;
+            self.next_damage_at = other.next_damage_at;
+            self.poison_caster_entity_id = other.poison_caster_entity_id;
+        }
+        StatusStackingResult::RefreshExisting
+    }
+
+    fn add_suppression(&mut self) {
+        self.suppressed += 1;
+    }
+
+    fn remove_suppression(&mut self) {
+        self.suppressed = self.suppressed.saturating_sub(1);
+    }
+
+    fn is_suppressed(&self) -> bool {
+        self.suppressed > 0
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
 